@@ -1,11 +1,12 @@
+use std::collections::{HashMap, VecDeque};
 use std::convert::TryFrom;
 use std::io::{Error, ErrorKind};
 
 use amethyst::{
     core::{components::Transform},
     derive::SystemDesc,
-    ecs::{Entities, Read, RunningTime, System, SystemData, World, Write, WriteStorage},
-    ecs::prelude::{Component, DenseVecStorage, Join, ParallelIterator, ParJoin},
+    ecs::{Entities, Entity, Read, RunningTime, System, SystemData, World, Write, WriteStorage},
+    ecs::prelude::{Component, DenseVecStorage, Join},
     input::VirtualKeyCode,
     renderer::{SpriteRender, Transparent},
     shred::ResourceId,
@@ -28,42 +29,117 @@ pub struct Player {
     shoot_cooldown: u8,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum CollideType {
-    Circle(f32)
+    /// Squared radius, then the real radius cached alongside it so
+    /// collision tests don't need to re-`sqrt` every tick.
+    Circle(f32, f32),
+    Aabb(f32, f32),
+    Capsule(f32, f32),
 }
 
 impl CollideType {
     pub fn is_collide_with_point(&self, me: &Vector3<f32>, other: &Vector3<f32>) -> bool {
         match self {
-            Self::Circle(r_2) => {
+            Self::Circle(r_2, _) => {
                 let x_distance = me.x - other.x;
                 let y_distance = me.y - other.y;
                 x_distance * x_distance + y_distance * y_distance <= *r_2
             }
+            Self::Aabb(half_w, half_h) => {
+                (other.x - me.x).abs() <= *half_w && (other.y - me.y).abs() <= *half_h
+            }
+            Self::Capsule(half_len, r) => {
+                let closest_y = other.y.max(me.y - half_len).min(me.y + half_len);
+                let x_distance = me.x - other.x;
+                let y_distance = closest_y - other.y;
+                x_distance * x_distance + y_distance * y_distance <= r * r
+            }
         }
     }
 
     pub fn is_collide_with(&self, me: &Vector3<f32>, other_collide: &CollideType, other: &Vector3<f32>) -> bool {
-        match self {
-            Self::Circle(r_2) => {
-                if *r_2 <= 0.0 {
-                    other_collide.is_collide_with_point(me, other)
-                } else {
-                    //todo: circle collide circle
-                    true
-                }
+        match (self, other_collide) {
+            // `is_collide_with_point(me, other)` treats `me` as *its own*
+            // position and `other` as the point to test (see the call sites
+            // elsewhere in this file) -- that's asymmetric for `Capsule`, so
+            // the degenerate zero-radius-circle shortcut below must swap the
+            // args: `other_collide` owns the `other` position, and the point
+            // being tested is this circle's own `me`.
+            (Self::Circle(r_2, _), _) if *r_2 <= 0.0 => other_collide.is_collide_with_point(other, me),
+            (Self::Circle(_, r_a), Self::Circle(_, r_b)) => {
+                let dx = me.x - other.x;
+                let dy = me.y - other.y;
+                let r_sum = r_a + r_b;
+                dx * dx + dy * dy <= r_sum * r_sum
+            }
+            (Self::Circle(_, r), Self::Aabb(half_w, half_h)) => circle_vs_aabb(me, *r, other, *half_w, *half_h),
+            (Self::Aabb(half_w, half_h), Self::Circle(_, r)) => circle_vs_aabb(other, *r, me, *half_w, *half_h),
+            (Self::Circle(_, r_circle), Self::Capsule(half_len, r_cap)) => capsule_vs_circle(other, *half_len, *r_cap, me, *r_circle),
+            (Self::Capsule(half_len, r_cap), Self::Circle(_, r_circle)) => capsule_vs_circle(me, *half_len, *r_cap, other, *r_circle),
+            (Self::Aabb(a_half_w, a_half_h), Self::Aabb(b_half_w, b_half_h)) => {
+                (me.x - other.x).abs() <= a_half_w + b_half_w && (me.y - other.y).abs() <= a_half_h + b_half_h
             }
+            (Self::Aabb(half_w, half_h), Self::Capsule(half_len, r)) => capsule_vs_aabb(other, *half_len, *r, me, *half_w, *half_h),
+            (Self::Capsule(half_len, r), Self::Aabb(half_w, half_h)) => capsule_vs_aabb(me, *half_len, *r, other, *half_w, *half_h),
+            (Self::Capsule(a_half_len, a_r), Self::Capsule(b_half_len, b_r)) => capsule_vs_capsule(me, *a_half_len, *a_r, other, *b_half_len, *b_r),
         }
     }
 }
 
+/// Closest-point-on-box test: clamp the circle center into the AABB's
+/// extents, then compare the leftover distance against the radius.
+fn circle_vs_aabb(circle_pos: &Vector3<f32>, r: f32, aabb_pos: &Vector3<f32>, half_w: f32, half_h: f32) -> bool {
+    let closest_x = circle_pos.x.max(aabb_pos.x - half_w).min(aabb_pos.x + half_w);
+    let closest_y = circle_pos.y.max(aabb_pos.y - half_h).min(aabb_pos.y + half_h);
+    let dx = circle_pos.x - closest_x;
+    let dy = circle_pos.y - closest_y;
+    dx * dx + dy * dy <= r * r
+}
+
+/// Point-to-segment test: the capsule's spine runs along the world y-axis
+/// (the entity's rotation is not applied), so the closest point on it to the
+/// circle is just the circle's y clamped to the spine's extent.
+fn capsule_vs_circle(capsule_pos: &Vector3<f32>, half_len: f32, r_cap: f32, circle_pos: &Vector3<f32>, r_circle: f32) -> bool {
+    let closest_y = circle_pos.y.max(capsule_pos.y - half_len).min(capsule_pos.y + half_len);
+    let dx = capsule_pos.x - circle_pos.x;
+    let dy = closest_y - circle_pos.y;
+    let r_sum = r_cap + r_circle;
+    dx * dx + dy * dy <= r_sum * r_sum
+}
+
+fn capsule_vs_aabb(capsule_pos: &Vector3<f32>, half_len: f32, r: f32, aabb_pos: &Vector3<f32>, half_w: f32, half_h: f32) -> bool {
+    let closest_y = aabb_pos.y.max(capsule_pos.y - half_len).min(capsule_pos.y + half_len);
+    let closest_point = Vector3::new(capsule_pos.x, closest_y, capsule_pos.z);
+    circle_vs_aabb(&closest_point, r, aabb_pos, half_w, half_h)
+}
+
+/// Both capsules run along parallel (world-y, unrotated) spines, so the true closest
+/// distance is either the perpendicular x-gap (when the spines' y-extents
+/// overlap) or the distance between their nearest endpoints.
+fn capsule_vs_capsule(a_pos: &Vector3<f32>, a_half_len: f32, a_r: f32, b_pos: &Vector3<f32>, b_half_len: f32, b_r: f32) -> bool {
+    let (a_lo, a_hi) = (a_pos.y - a_half_len, a_pos.y + a_half_len);
+    let (b_lo, b_hi) = (b_pos.y - b_half_len, b_pos.y + b_half_len);
+    let y_gap = if a_hi < b_lo {
+        b_lo - a_hi
+    } else if b_hi < a_lo {
+        a_lo - b_hi
+    } else {
+        0.0
+    };
+    let x_gap = a_pos.x - b_pos.x;
+    let r_sum = a_r + b_r;
+    x_gap * x_gap + y_gap * y_gap <= r_sum * r_sum
+}
+
 impl TryFrom<(u8, Vec<f32>)> for CollideType {
     type Error = Error;
 
     fn try_from((value, args): (u8, Vec<f32>)) -> Result<Self, Self::Error> {
         match value {
-            10 => Ok(CollideType::Circle(args[0] * args[0])),
+            10 => Ok(CollideType::Circle(args[0] * args[0], args[0])),
+            11 => Ok(CollideType::Aabb(args[0], args[1])),
+            12 => Ok(CollideType::Capsule(args[0], args[1])),
             _ => Err(Error::new(ErrorKind::InvalidData, "No such value for CollideType: ".to_owned() + &*value.to_string()))
         }
     }
@@ -73,11 +149,45 @@ impl CollideType {
     pub fn get_arg_count(byte: u8) -> usize {
         match byte {
             10 => 1,
+            11 => 2,
+            12 => 2,
             _ => panic!("Not collide byte: {}", byte)
         }
     }
 }
 
+impl CollideType {
+    /// Appends this shape's opcode byte and args to `buf`, mirroring the
+    /// `TryFrom<(u8, Vec<f32>)>` encoding above so a snapshot can round-trip
+    /// an enemy bullet's collider without knowing its variant in advance.
+    fn encode(&self, buf: &mut Vec<u8>) {
+        let (tag, a, b) = match self {
+            Self::Circle(_, r) => (10u8, *r, 0.0),
+            Self::Aabb(half_w, half_h) => (11u8, *half_w, *half_h),
+            Self::Capsule(half_len, r) => (12u8, *half_len, *r),
+        };
+        buf.push(tag);
+        buf.extend_from_slice(&a.to_le_bytes());
+        buf.extend_from_slice(&b.to_le_bytes());
+    }
+
+    /// Inverse of `encode`. Panics on a malformed buffer, same as `restore`.
+    fn decode(bytes: &[u8], cursor: &mut usize) -> Self {
+        let tag = bytes[*cursor];
+        *cursor += 1;
+        let a = f32::from_le_bytes(bytes[*cursor..*cursor + 4].try_into().unwrap());
+        *cursor += 4;
+        let b = f32::from_le_bytes(bytes[*cursor..*cursor + 4].try_into().unwrap());
+        *cursor += 4;
+        match tag {
+            10 => Self::Circle(a * a, a),
+            11 => Self::Aabb(a, b),
+            12 => Self::Capsule(a, b),
+            _ => panic!("Not collide byte in snapshot: {}", tag),
+        }
+    }
+}
+
 impl Player {
     pub fn new(speed: f32) -> Self {
         Self {
@@ -93,6 +203,204 @@ impl Component for Player {
     type Storage = DenseVecStorage<Self>;
 }
 
+/// The arena rectangle, its out-of-bounds cull margin, and the wall
+/// thickness. Replaces the magic numbers that used to be duplicated between
+/// `is_out_of_game` and the player movement clamp.
+#[derive(Clone, Copy)]
+pub struct Playfield {
+    pub min_x: f32,
+    pub min_y: f32,
+    pub max_x: f32,
+    pub max_y: f32,
+    pub cull_margin: f32,
+    pub wall_thickness: f32,
+    walls_spawned: bool,
+}
+
+impl Default for Playfield {
+    fn default() -> Self {
+        Self {
+            min_x: 0.0,
+            min_y: 0.0,
+            max_x: 1600.0,
+            max_y: 900.0,
+            cull_margin: 100.0,
+            wall_thickness: 20.0,
+            walls_spawned: false,
+        }
+    }
+}
+
+impl Playfield {
+    pub fn clamp_x(&self, x: f32) -> f32 {
+        x.max(self.min_x).min(self.max_x)
+    }
+
+    pub fn clamp_y(&self, y: f32) -> f32 {
+        y.max(self.min_y).min(self.max_y)
+    }
+}
+
+/// Static collider for an arena boundary wall, spawned once by `spawn_walls`.
+pub struct Wall {
+    pub collide: CollideType,
+}
+
+impl Component for Wall {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// Queue of named sound cues raised this tick. `GameSystem` only decides
+/// *when* a cue fires; decoding and playing the OGG data back lives in a
+/// dedicated audio system that drains this queue once per frame.
+#[derive(Default)]
+pub struct SoundQueue {
+    pending: Vec<String>,
+}
+
+impl SoundQueue {
+    pub fn play(&mut self, name: &str) {
+        self.pending.push(name.to_string());
+    }
+
+    pub fn drain(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.pending)
+    }
+}
+
+const AUDIO_SETTINGS_PATH: &str = "audio_settings.cfg";
+
+/// Music/SFX volume and mute state. `GameSystem::setup` calls
+/// `AudioSettings::load()` and inserts the result into the `World` before
+/// this system ever runs, and `toggle_mute` saves back to disk whenever the
+/// player changes the one setting this file exposes a control for.
+pub struct AudioSettings {
+    pub music_volume: f32,
+    pub sfx_volume: f32,
+    pub muted: bool,
+    /// Edge-detects the mute keybind so holding it down doesn't toggle every
+    /// tick; not persisted.
+    mute_key_latch: bool,
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        Self { music_volume: 0.8, sfx_volume: 0.8, muted: false, mute_key_latch: false }
+    }
+}
+
+impl AudioSettings {
+    pub fn load() -> Self {
+        std::fs::read_to_string(AUDIO_SETTINGS_PATH)
+            .ok()
+            .and_then(|contents| {
+                let mut parts = contents.trim().split(',');
+                let music_volume = parts.next()?.parse().ok()?;
+                let sfx_volume = parts.next()?.parse().ok()?;
+                let muted = parts.next()? == "true";
+                Some(Self { music_volume, sfx_volume, muted, mute_key_latch: false })
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) {
+        let contents = format!("{},{},{}", self.music_volume, self.sfx_volume, self.muted);
+        let _ = std::fs::write(AUDIO_SETTINGS_PATH, contents);
+    }
+
+    /// Flips `muted` and persists it immediately; call once per keypress
+    /// (see the `mute_key_latch` edge-detection in `process_player`).
+    pub fn toggle_mute(&mut self) {
+        self.muted = !self.muted;
+        self.save();
+    }
+}
+
+/// Seeded PRNG for all gameplay randomness. Reading from any ambient source
+/// (thread_rng, system time, ...) would make re-simulation from a restored
+/// snapshot diverge, so every roll a script or system needs must go through
+/// this instead. Lives on `CoreStorage` (alongside `tick`/`tick_sign`) rather
+/// than as its own resource, since it's part of the same rollback-critical
+/// state and should never be able to drift out of sync with it.
+pub struct SimRng {
+    state: u64,
+}
+
+impl Default for SimRng {
+    fn default() -> Self {
+        Self { state: 0x2545_F491_4F6C_DD1D }
+    }
+}
+
+impl SimRng {
+    pub fn seed(&mut self, seed: u64) {
+        self.state = seed;
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.state = x;
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+const ROLLBACK_CAPACITY: usize = 128;
+
+/// Ring buffer of per-tick snapshots, keyed by tick number. When a late
+/// remote input disagrees with the locally-predicted one, the session layer
+/// looks up the snapshot for that tick, calls `GameSystemData::restore` on
+/// it and re-runs `GameSystem` forward with the corrected inputs.
+#[derive(Default)]
+pub struct RollbackBuffer {
+    snapshots: VecDeque<(u64, Vec<u8>)>,
+}
+
+impl RollbackBuffer {
+    pub fn push(&mut self, tick: u64, snapshot: Vec<u8>) {
+        if self.snapshots.len() == ROLLBACK_CAPACITY {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back((tick, snapshot));
+    }
+
+    pub fn get(&self, tick: u64) -> Option<&[u8]> {
+        self.snapshots.iter().find(|(t, _)| *t == tick).map(|(_, data)| data.as_slice())
+    }
+}
+
+/// Per-kind live counts for enemy bullets, rebuilt once at the start of
+/// every tick so scripts can cap their own output (e.g. "stop firing once
+/// 40 of my bullets are already on screen") without per-bullet bookkeeping
+/// in Lua.
+#[derive(Default)]
+pub struct BulletManager {
+    counts: HashMap<u16, u32>,
+}
+
+impl BulletManager {
+    pub fn rebuild<'a>(&mut self, enemy_bullets: &WriteStorage<'a, EnemyBullet>) {
+        self.counts.clear();
+        for bullet in enemy_bullets.join() {
+            *self.counts.entry(bullet.kind).or_insert(0) += 1;
+        }
+    }
+
+    pub fn count_bullets(&self, kind: u16) -> u32 {
+        *self.counts.get(&kind).unwrap_or(&0)
+    }
+
+    pub fn count_bullets_multi(&self, kinds: [u16; 3]) -> u32 {
+        kinds.iter().map(|kind| self.count_bullets(*kind)).sum()
+    }
+}
+
 #[derive(SystemData)]
 pub struct GameSystemData<'a> {
     transforms: WriteStorage<'a, Transform>,
@@ -107,6 +415,294 @@ pub struct GameSystemData<'a> {
     enemy_bullets: WriteStorage<'a, EnemyBullet>,
     animations: (WriteStorage<'a, InvertColorCircle>, WriteStorage<'a, InvertColorAnimation>),
     script_manager: Write<'a, ScriptManager>,
+    rollback: Write<'a, RollbackBuffer>,
+    bullet_manager: Write<'a, BulletManager>,
+    playfield: Write<'a, Playfield>,
+    walls: WriteStorage<'a, Wall>,
+    sound_queue: Write<'a, SoundQueue>,
+    audio_settings: Write<'a, AudioSettings>,
+}
+
+impl<'a> GameSystemData<'a> {
+    /// Number of live enemy bullets tagged with `kind`, as of the start of
+    /// the current tick. Wiring this through to a Lua-callable function on
+    /// `ScriptGameData` is the script module's job; this is the data side.
+    pub fn count_bullets(&self, kind: u16) -> u32 {
+        self.bullet_manager.count_bullets(kind)
+    }
+
+    pub fn count_bullets_multi(&self, kinds: [u16; 3]) -> u32 {
+        self.bullet_manager.count_bullets_multi(kinds)
+    }
+
+    /// Arena bounds, so spawn patterns can place bullets relative to the
+    /// current edges instead of baked-in constants. As with the bullet
+    /// counts above, surfacing this as a Lua-callable is the script module's
+    /// job; this is the data side.
+    pub fn playfield(&self) -> &Playfield {
+        &self.playfield
+    }
+
+    /// Queues a named sound cue, unless the player has muted audio.
+    pub fn play_sound(&mut self, name: &str) {
+        if !self.audio_settings.muted {
+            self.sound_queue.play(name);
+        }
+    }
+
+    /// Serializes the bit-for-bit-reproducible portion of the simulation:
+    /// the tick counter, the RNG stream, the net-id allocator, the player,
+    /// every enemy's hp/pos/script state, and every player/enemy bullet's
+    /// full physical + script state.
+    ///
+    /// Player and enemy *entities* are only ever updated in place on restore
+    /// (see `restore` below) — their construction (texture handles, collide
+    /// shape, script args) happens outside this file, so this module can't
+    /// recreate one that was deleted after the snapshotted tick. Their
+    /// `ScriptContext` state, however, is owned by this file and does
+    /// round-trip. Bullets are fully owned by this file too, so those
+    /// round-trip exactly: `restore` deletes every live bullet and rebuilds
+    /// the snapshotted set from scratch, which is what makes resimulation
+    /// from here byte-identical for them.
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&(self.core.tick as u64).to_le_bytes());
+        buf.extend_from_slice(&self.core.rng.state.to_le_bytes());
+        // Every bullet's net_id is pulled from this counter, so it has to be
+        // rewound along with everything else -- otherwise bullets spawned
+        // while resimulating a restored snapshot pick up net_ids continuing
+        // from the discarded timeline instead of the snapshotted one.
+        buf.extend_from_slice(&self.core.next_net_id.to_le_bytes());
+
+        match self.core.player {
+            Some(entity) => {
+                buf.push(1);
+                let pos = self.transforms.get(entity).unwrap().translation();
+                let player = self.players.get(entity).unwrap();
+                buf.extend_from_slice(&pos.x.to_le_bytes());
+                buf.extend_from_slice(&pos.y.to_le_bytes());
+                buf.extend_from_slice(&pos.z.to_le_bytes());
+                buf.push(player.shoot_cooldown);
+            }
+            None => buf.push(0),
+        }
+
+        let mut enemies: Vec<_> = (&self.enemies, &self.transforms, &self.entities).join()
+            .map(|(enemy, tran, e)| (e.id(), enemy.hp, *tran.translation(), enemy.script.snapshot()))
+            .collect();
+        enemies.sort_by_key(|(id, ..)| *id);
+        buf.extend_from_slice(&(enemies.len() as u32).to_le_bytes());
+        for (id, hp, pos, script_blob) in enemies {
+            buf.extend_from_slice(&id.to_le_bytes());
+            buf.extend_from_slice(&hp.to_le_bytes());
+            buf.extend_from_slice(&pos.x.to_le_bytes());
+            buf.extend_from_slice(&pos.y.to_le_bytes());
+            buf.extend_from_slice(&pos.z.to_le_bytes());
+            buf.extend_from_slice(&(script_blob.len() as u32).to_le_bytes());
+            buf.extend_from_slice(&script_blob);
+        }
+
+        let mut player_bullets: Vec<_> = (&self.player_bullets, &self.transforms).join()
+            .map(|(bullet, tran)| (bullet.net_id, bullet.damage, bullet.life, *tran.translation()))
+            .collect();
+        player_bullets.sort_by_key(|(net_id, ..)| *net_id);
+        buf.extend_from_slice(&(player_bullets.len() as u32).to_le_bytes());
+        for (net_id, damage, life, pos) in player_bullets {
+            buf.extend_from_slice(&net_id.to_le_bytes());
+            buf.extend_from_slice(&damage.to_le_bytes());
+            buf.extend_from_slice(&life.to_le_bytes());
+            buf.extend_from_slice(&pos.x.to_le_bytes());
+            buf.extend_from_slice(&pos.y.to_le_bytes());
+            buf.extend_from_slice(&pos.z.to_le_bytes());
+        }
+
+        let mut enemy_bullets: Vec<_> = (&self.enemy_bullets, &self.transforms).join()
+            .map(|(bullet, tran)| (bullet.net_id, bullet, tran))
+            .collect();
+        enemy_bullets.sort_by_key(|(net_id, ..)| *net_id);
+        buf.extend_from_slice(&(enemy_bullets.len() as u32).to_le_bytes());
+        for (net_id, bullet, tran) in enemy_bullets {
+            let pos = tran.translation();
+            buf.extend_from_slice(&net_id.to_le_bytes());
+            buf.extend_from_slice(&pos.x.to_le_bytes());
+            buf.extend_from_slice(&pos.y.to_le_bytes());
+            buf.extend_from_slice(&pos.z.to_le_bytes());
+            for c in tran.rotation().coords.iter() {
+                buf.extend_from_slice(&c.to_le_bytes());
+            }
+            buf.extend_from_slice(&bullet.vel.x.to_le_bytes());
+            buf.extend_from_slice(&bullet.vel.y.to_le_bytes());
+            buf.extend_from_slice(&bullet.accel.x.to_le_bytes());
+            buf.extend_from_slice(&bullet.accel.y.to_le_bytes());
+            buf.extend_from_slice(&bullet.angular.to_le_bytes());
+            buf.extend_from_slice(&bullet.life.to_le_bytes());
+            buf.extend_from_slice(&bullet.kind.to_le_bytes());
+            let name_bytes = bullet.sprite_name.as_bytes();
+            buf.extend_from_slice(&(name_bytes.len() as u32).to_le_bytes());
+            buf.extend_from_slice(name_bytes);
+            bullet.collide.encode(&mut buf);
+            let script_blob = bullet.script.snapshot();
+            buf.extend_from_slice(&(script_blob.len() as u32).to_le_bytes());
+            buf.extend_from_slice(&script_blob);
+        }
+
+        buf
+    }
+
+    /// Inverse of `snapshot`. Panics on a malformed buffer; the buffer only
+    /// ever comes from a prior `snapshot()` call, so a mismatch means a bug
+    /// in the rollback wiring, not bad network input.
+    pub fn restore(&mut self, bytes: &[u8]) {
+        let mut cursor = 0usize;
+        let mut read_u64 = |cursor: &mut usize| {
+            let v = u64::from_le_bytes(bytes[*cursor..*cursor + 8].try_into().unwrap());
+            *cursor += 8;
+            v
+        };
+        let mut read_f32 = |cursor: &mut usize| {
+            let v = f32::from_le_bytes(bytes[*cursor..*cursor + 4].try_into().unwrap());
+            *cursor += 4;
+            v
+        };
+        let mut read_u32 = |cursor: &mut usize| {
+            let v = u32::from_le_bytes(bytes[*cursor..*cursor + 4].try_into().unwrap());
+            *cursor += 4;
+            v
+        };
+
+        self.core.tick = read_u64(&mut cursor) as _;
+        self.core.rng.state = read_u64(&mut cursor);
+        self.core.next_net_id = read_u32(&mut cursor);
+
+        let has_player = bytes[cursor];
+        cursor += 1;
+        if has_player == 1 {
+            let x = read_f32(&mut cursor);
+            let y = read_f32(&mut cursor);
+            let z = read_f32(&mut cursor);
+            let shoot_cooldown = bytes[cursor];
+            cursor += 1;
+            // If the player died after the snapshotted tick, there's no entity left to
+            // patch: resurrecting one needs the texture handle and speed/radius that
+            // `Player::new` is called with, which happens outside this file. Rolling
+            // back past a death is a known gap until player construction moves here.
+            if let Some(entity) = self.core.player {
+                if let Some(tran) = self.transforms.get_mut(entity) {
+                    tran.set_translation_xyz(x, y, z);
+                }
+                if let Some(player) = self.players.get_mut(entity) {
+                    player.shoot_cooldown = shoot_cooldown;
+                }
+            }
+        }
+
+        let enemy_count = read_u32(&mut cursor);
+        let by_id: HashMap<u32, Entity> = (&self.entities,).join()
+            .map(|(e,)| (e.id(), e))
+            .collect();
+        for _ in 0..enemy_count {
+            let id = read_u32(&mut cursor);
+            let hp = read_f32(&mut cursor);
+            let x = read_f32(&mut cursor);
+            let y = read_f32(&mut cursor);
+            let z = read_f32(&mut cursor);
+            let script_len = read_u32(&mut cursor) as usize;
+            let script_bytes = &bytes[cursor..cursor + script_len];
+            cursor += script_len;
+            // Same limitation as the player above: an enemy deleted after the
+            // snapshotted tick can't be rebuilt from here, since its script/collide
+            // shape/texture are assigned by the stage code that spawned it. Its
+            // `ScriptContext` *is* owned by this file though, so that part is
+            // restored in place just like it is for enemy bullets below.
+            if let Some(&entity) = by_id.get(&id) {
+                if let Some(enemy) = self.enemies.get_mut(entity) {
+                    enemy.hp = hp;
+                    enemy.script = ScriptContext::restore(&mut self.script_manager, script_bytes);
+                }
+                if let Some(tran) = self.transforms.get_mut(entity) {
+                    tran.set_translation_xyz(x, y, z);
+                }
+            }
+        }
+
+        // Bullets are fully owned by this file, so unlike the player/enemies above,
+        // restore doesn't try to patch them in place: it deletes every bullet that's
+        // currently alive (including ones spawned after the snapshotted tick) and
+        // rebuilds exactly the snapshotted set, net id and all. That's what makes
+        // resimulating forward from here byte-identical for bullets.
+        let stale_player_bullets: Vec<Entity> = (&self.player_bullets, &self.entities).join()
+            .map(|(_, e)| e)
+            .collect();
+        for entity in stale_player_bullets {
+            self.entities.delete(entity).expect("delete stale player bullet failed");
+        }
+        let player_bullet_count = read_u32(&mut cursor);
+        for _ in 0..player_bullet_count {
+            let net_id = read_u32(&mut cursor);
+            let damage = read_f32(&mut cursor);
+            let life = read_u32(&mut cursor);
+            let x = read_f32(&mut cursor);
+            let y = read_f32(&mut cursor);
+            let z = read_f32(&mut cursor);
+            let mut pos = Transform::default();
+            pos.set_translation_xyz(x, y, z);
+            pos.set_scale(Vector3::new(0.5, 0.5, 1.0));
+            self.entities.build_entity()
+                .with(pos, &mut self.transforms)
+                .with(PlayerBullet { net_id, damage, life }, &mut self.player_bullets)
+                .with(self.texture_handles.player_bullet.clone().unwrap(), &mut self.sprite_renders)
+                .with(Transparent, &mut self.transparent)
+                .build();
+        }
+
+        let stale_enemy_bullets: Vec<Entity> = (&self.enemy_bullets, &self.entities).join()
+            .map(|(_, e)| e)
+            .collect();
+        for entity in stale_enemy_bullets {
+            self.entities.delete(entity).expect("delete stale enemy bullet failed");
+        }
+        let enemy_bullet_count = read_u32(&mut cursor);
+        for _ in 0..enemy_bullet_count {
+            let net_id = read_u32(&mut cursor);
+            let x = read_f32(&mut cursor);
+            let y = read_f32(&mut cursor);
+            let z = read_f32(&mut cursor);
+            let (rot_i, rot_j, rot_k, rot_w) = (read_f32(&mut cursor), read_f32(&mut cursor), read_f32(&mut cursor), read_f32(&mut cursor));
+            let vel = Vector3::new(read_f32(&mut cursor), read_f32(&mut cursor), 0.0);
+            let accel = Vector3::new(read_f32(&mut cursor), read_f32(&mut cursor), 0.0);
+            let angular = read_f32(&mut cursor);
+            let life = read_u32(&mut cursor);
+            let kind = u16::from_le_bytes(bytes[cursor..cursor + 2].try_into().unwrap());
+            cursor += 2;
+            let name_len = read_u32(&mut cursor) as usize;
+            let sprite_name = String::from_utf8(bytes[cursor..cursor + name_len].to_vec()).unwrap();
+            cursor += name_len;
+            let collide = CollideType::decode(bytes, &mut cursor);
+            let script_len = read_u32(&mut cursor) as usize;
+            let script_context = ScriptContext::restore(&mut self.script_manager, &bytes[cursor..cursor + script_len]);
+            cursor += script_len;
+
+            let mut pos = Transform::default();
+            pos.set_translation_xyz(x, y, z);
+            pos.set_rotation(nalgebra::UnitQuaternion::new_unchecked(nalgebra::Quaternion::new(rot_w, rot_i, rot_j, rot_k)));
+            self.entities.build_entity()
+                .with(pos, &mut self.transforms)
+                .with(EnemyBullet {
+                    net_id,
+                    collide,
+                    script: script_context,
+                    sprite_name: sprite_name.clone(),
+                    vel,
+                    accel,
+                    angular,
+                    kind,
+                    life,
+                }, &mut self.enemy_bullets)
+                .with(self.texture_handles.bullets.get(&sprite_name).unwrap().clone(), &mut self.sprite_renders)
+                .with(Transparent, &mut self.transparent)
+                .build();
+        }
+    }
 }
 
 
@@ -116,8 +712,18 @@ pub struct GameSystem;
 impl<'a> System<'a> for GameSystem {
     type SystemData = GameSystemData<'a>;
 
+    fn setup(&mut self, world: &mut World) {
+        Self::SystemData::setup(world);
+        world.insert(AudioSettings::load());
+    }
 
     fn run(&mut self, mut data: Self::SystemData) {
+        if !data.playfield.walls_spawned {
+            let playfield = *data.playfield;
+            spawn_walls(&mut data, &playfield);
+            data.playfield.walls_spawned = true;
+        }
+
         if data.core.tick_sign {
             let mut game_data = ScriptGameData {
                 tran: None,
@@ -131,10 +737,28 @@ impl<'a> System<'a> for GameSystem {
 
             data.core.tick_sign = false;
             data.core.tick += 1;
-            'bullet_for: for (bullet, bullet_entity) in (&data.player_bullets, &data.entities).join() {
+
+            let mut bullet_entities: Vec<Entity> = (&data.player_bullets, &data.entities).join()
+                .map(|(_, e)| e)
+                .collect();
+            bullet_entities.sort_by_key(|e| e.id());
+            let mut enemy_entities: Vec<Entity> = (&data.enemies, &data.entities).join()
+                .map(|(_, e)| e)
+                .collect();
+            enemy_entities.sort_by_key(|e| e.id());
+            data.bullet_manager.rebuild(&data.enemy_bullets);
+
+            'bullet_for: for bullet_entity in bullet_entities {
+                let bullet = data.player_bullets.get_mut(bullet_entity).unwrap();
+                bullet.life = bullet.life.saturating_sub(1);
+                if bullet.life == 0 {
+                    data.entities.delete(bullet_entity).expect("delete bullet entity failed");
+                    continue 'bullet_for;
+                }
                 {
                     let bullet_pos = data.transforms.get(bullet_entity).unwrap().translation();
-                    for (enemy, enemy_entity) in (&mut data.enemies, &data.entities).join() {
+                    for &enemy_entity in &enemy_entities {
+                        let enemy = data.enemies.get_mut(enemy_entity).unwrap();
                         if enemy.hp <= 0.0 {
                             continue;
                         }
@@ -142,10 +766,20 @@ impl<'a> System<'a> for GameSystem {
                         let enemy_pos = enemy_tran.translation();
                         if enemy.collide.is_collide_with_point(enemy_pos, bullet_pos) {
                             enemy.hp -= bullet.damage;
+                            // `data.play_sound` needs `&mut data` wholesale, which would
+                            // conflict with the live `enemy`/`bullet` borrows still in
+                            // scope here -- inline the check instead, same as the
+                            // `PlaySound` script command arm below.
+                            if !data.audio_settings.muted {
+                                data.sound_queue.play("enemy_hit");
+                            }
                             if enemy.hp <= 0.0 {
                                 println!("Anye hp left: 0.0");
                                 data.entities.delete(enemy_entity).expect("delete enemy entity failed");
-                                boss_die_anime(&data.entities, (&mut data.animations.0, &mut data.animations.1), enemy_pos);
+                                boss_die_anime(&data.entities, (&mut data.animations.0, &mut data.animations.1), enemy_pos, &mut data.core.rng);
+                                if !data.audio_settings.muted {
+                                    data.sound_queue.play("boss_die");
+                                }
                             } else {
                                 println!("Anye hp left: {}", enemy.hp);
                             }
@@ -157,37 +791,87 @@ impl<'a> System<'a> for GameSystem {
                 }
                 let pos = data.transforms.get_mut(bullet_entity).unwrap();
                 pos.move_up(30.0);
-                if is_out_of_game(pos) {
+                if is_out_of_game(pos, &data.playfield) {
                     data.entities.delete(bullet_entity).expect("delete bullet entity failed");
                 }
             }
 
-            for (enemy_bullet, enemy_entity) in (&mut data.enemy_bullets, &data.entities).join() {
+            let mut enemy_bullet_entities: Vec<Entity> = (&data.enemy_bullets, &data.entities).join()
+                .map(|(_, e)| e)
+                .collect();
+            enemy_bullet_entities.sort_by_key(|e| e.id());
+            for enemy_entity in enemy_bullet_entities {
                 let enemy_tran = data.transforms.get_mut(enemy_entity).unwrap();
-                if is_out_of_game(enemy_tran) {
+                if is_out_of_game(enemy_tran, &data.playfield) {
                     data.entities.delete(enemy_entity).expect("delete enemy entity failed");
                     continue;
                 }
                 game_data.tran = Some((*enemy_tran).clone());
+                let enemy_bullet = data.enemy_bullets.get_mut(enemy_entity).unwrap();
+                enemy_bullet.life = enemy_bullet.life.saturating_sub(1);
+                if enemy_bullet.life == 0 {
+                    data.entities.delete(enemy_entity).expect("delete enemy entity failed");
+                    continue;
+                }
+                let enemy_bullet = data.enemy_bullets.get_mut(enemy_entity).unwrap();
                 enemy_bullet.script.execute_function(&"tick".to_string(), &mut game_data);
+                let enemy_tran = data.transforms.get_mut(enemy_entity).unwrap();
                 while let Some(x) = game_data.submit_command.pop() {
                     match x {
                         crate::script::ScriptGameCommand::MoveUp(v) => {
                             enemy_tran.move_up(v);
                         }
+                        crate::script::ScriptGameCommand::SetVelocity(vx, vy) => {
+                            enemy_bullet.vel.x = vx;
+                            enemy_bullet.vel.y = vy;
+                        }
+                        crate::script::ScriptGameCommand::Accelerate(ax, ay) => {
+                            enemy_bullet.accel.x = ax;
+                            enemy_bullet.accel.y = ay;
+                        }
+                        crate::script::ScriptGameCommand::SetAngularVelocity(dps) => {
+                            enemy_bullet.angular = dps;
+                        }
+                        crate::script::ScriptGameCommand::HomeToPlayer(turn_rate, max_speed) => {
+                            home_to_player(enemy_bullet, enemy_tran, &game_data.player_tran, turn_rate, max_speed);
+                        }
+                        crate::script::ScriptGameCommand::PlaySound(name) => {
+                            if !data.audio_settings.muted {
+                                data.sound_queue.play(&name);
+                            }
+                        }
                         _ => {}
                     }
                 }
-            }
+                integrate_bullet_motion(enemy_bullet, enemy_tran);
 
+                let hit_wall = {
+                    let enemy_tran = data.transforms.get(enemy_entity).unwrap();
+                    let enemy_pos = enemy_tran.translation();
+                    let bullet = data.enemy_bullets.get(enemy_entity).unwrap();
+                    (&data.walls, &data.transforms).join()
+                        .find(|(wall, wall_tran)| bullet.collide.is_collide_with(enemy_pos, &wall.collide, wall_tran.translation()))
+                        .map(|(wall, _)| wall.collide)
+                };
+                if let Some(wall_collide) = hit_wall {
+                    let enemy_tran = data.transforms.get_mut(enemy_entity).unwrap();
+                    let enemy_bullet = data.enemy_bullets.get_mut(enemy_entity).unwrap();
+                    bounce_off_wall(enemy_bullet, enemy_tran, &wall_collide, &data.playfield);
+                }
+            }
 
-            for (enemy, enemy_entity) in (&mut data.enemies, &data.entities).join() {
+            let mut live_enemy_entities: Vec<Entity> = (&data.enemies, &data.entities).join()
+                .map(|(_, e)| e)
+                .collect();
+            live_enemy_entities.sort_by_key(|e| e.id());
+            for enemy_entity in live_enemy_entities {
+                let enemy = data.enemies.get_mut(enemy_entity).unwrap();
                 let enemy_tran = data.transforms.get(enemy_entity).unwrap();
                 game_data.tran = Some((*enemy_tran).clone());
                 enemy.script.execute_function(&"tick".to_string(), &mut game_data);
                 while let Some(x) = game_data.submit_command.pop() {
                     match x {
-                        crate::script::ScriptGameCommand::SummonBullet(name, x, y, z, angle, collide, script, args) => {
+                        crate::script::ScriptGameCommand::SummonBullet(name, x, y, z, angle, collide, script, args, kind, lifetime) => {
                             let script_context;
                             if let Some(script) = game_data.script_manager.as_mut().unwrap().get_script(&script) {
                                 script_context = ScriptContext::new(script, args);
@@ -198,9 +882,20 @@ impl<'a> System<'a> for GameSystem {
                             let mut pos = Transform::default();
                             pos.set_translation_xyz(x, y, z);
                             pos.set_rotation_z_axis(angle / 180.0 * PI);
+                            let net_id = data.core.next_net_id();
                             data.entities.build_entity()
                                 .with(pos, &mut data.transforms)
-                                .with(EnemyBullet { collide, script: script_context }, &mut data.enemy_bullets)
+                                .with(EnemyBullet {
+                                    net_id,
+                                    collide,
+                                    script: script_context,
+                                    sprite_name: name.clone(),
+                                    vel: Vector3::new(0.0, 0.0, 0.0),
+                                    accel: Vector3::new(0.0, 0.0, 0.0),
+                                    angular: 0.0,
+                                    kind,
+                                    life: lifetime,
+                                }, &mut data.enemy_bullets)
                                 .with(data.texture_handles.bullets.get(&*name).unwrap().clone(), &mut data.sprite_renders)
                                 .with(Transparent, &mut data.transparent)
                                 .build();
@@ -210,6 +905,10 @@ impl<'a> System<'a> for GameSystem {
                 }
             }
             //tick if end
+
+            let tick = data.core.tick as u64;
+            let snapshot = data.snapshot();
+            data.rollback.push(tick, snapshot);
         }
     }
 
@@ -219,19 +918,28 @@ impl<'a> System<'a> for GameSystem {
 }
 
 fn process_player(data: &mut GameSystemData, game_data: &mut ScriptGameData) {
+    let mut shot_fired = false;
+    let mut player_died = false;
     if let Some(entity) = data.core.player {
         let player = data.players.get_mut(entity).unwrap();
         let pos = data.transforms.get_mut(entity).unwrap();
         let input = data.core.cur_input.as_ref().unwrap();
         let is_walk = input.pressing.contains(&VirtualKeyCode::LShift);
+
+        let mute_pressed = input.pressing.contains(&VirtualKeyCode::M);
+        if mute_pressed && !data.audio_settings.mute_key_latch {
+            data.audio_settings.toggle_mute();
+        }
+        data.audio_settings.mute_key_latch = mute_pressed;
+
         let (mov_x, mov_y) = input.get_move(if is_walk {
             player.walk_speed
         } else {
             player.move_speed
         });
         let (raw_x, raw_y) = (pos.translation().x, pos.translation().y);
-        pos.set_translation_x((mov_x + raw_x).max(0.0).min(1600.0))
-            .set_translation_y((mov_y + raw_y).max(0.0).min(900.0));
+        pos.set_translation_x(data.playfield.clamp_x(mov_x + raw_x))
+            .set_translation_y(data.playfield.clamp_y(mov_y + raw_y));
 
         if is_walk {
             data.animations.0.insert(entity, InvertColorCircle {
@@ -249,41 +957,60 @@ fn process_player(data: &mut GameSystemData, game_data: &mut ScriptGameData) {
                 let mut pos = (*pos).clone();
                 pos.prepend_translation_z(-1.0);
                 pos.set_scale(Vector3::new(0.5, 0.5, 1.0));
+                let net_id = data.core.next_net_id();
                 data.entities.build_entity()
                     .with(pos, &mut data.transforms)
-                    .with(PlayerBullet { damage: 10.0 }, &mut data.player_bullets)
+                    .with(PlayerBullet { net_id, damage: 10.0, life: 120 }, &mut data.player_bullets)
                     .with(data.texture_handles.player_bullet.clone().unwrap(), &mut data.sprite_renders)
                     .with(Transparent, &mut data.transparent)
                     .build();
+                shot_fired = true;
             }
         } else {
             player.shoot_cooldown -= 1;
         }
         let pos = data.transforms.get(entity).unwrap();
 
-        let collide = CollideType::Circle(player.radius * player.radius);
+        let collide = CollideType::Circle(player.radius * player.radius, player.radius);
 
-        let die = (&data.enemy_bullets, &data.entities).par_join().any(|(bullet, enemy_bullet_entity)| {
+        let mut enemy_bullet_entities: Vec<Entity> = (&data.enemy_bullets, &data.entities).join()
+            .map(|(_, e)| e)
+            .collect();
+        enemy_bullet_entities.sort_by_key(|e| e.id());
+        let die = enemy_bullet_entities.iter().any(|&enemy_bullet_entity| {
+            let bullet = data.enemy_bullets.get(enemy_bullet_entity).unwrap();
             let enemy_tran = data.transforms.get(enemy_bullet_entity).unwrap();
-            if bullet.collide.is_collide_with(enemy_tran.translation(), &collide, pos.translation()) {
-                true
-            } else {
-                false
-            }
+            bullet.collide.is_collide_with(enemy_tran.translation(), &collide, pos.translation())
         });
         if die {
-            boss_die_anime(&mut data.entities, (&mut data.animations.0, &mut data.animations.1), pos.translation());
+            boss_die_anime(&mut data.entities, (&mut data.animations.0, &mut data.animations.1), pos.translation(), &mut data.core.rng);
             data.entities.delete(entity).expect("delete player entity failed");
             data.core.player = None;
+            player_died = true;
         }
     }
+
+    // Deferred until here so `data.play_sound` can take `&mut data` wholesale
+    // without fighting the `player`/`pos` borrows the block above needs.
+    if shot_fired {
+        data.play_sound("player_shoot");
+    }
+    if player_died {
+        data.play_sound("player_die");
+    }
 }
 
+/// `rng` adds a small deterministic jitter to the burst's spread speed so
+/// every death doesn't animate identically; since it's seeded on
+/// `CoreStorage`, the jitter still replays exactly the same way after a
+/// rollback resimulates this tick.
 fn boss_die_anime<'a>(entities: &Entities<'a>,
                       mut animations: (&mut WriteStorage<'a, InvertColorCircle>, &mut WriteStorage<'a, InvertColorAnimation>),
-                      enemy_pos: &Vector3<f32>) {
+                      enemy_pos: &Vector3<f32>,
+                      rng: &mut SimRng) {
+    let jitter = 1.0 + (rng.next_f32() - 0.5) * 0.2;
     let last_seconds = 5.0;
-    let spread_per_second = 300.0;
+    let spread_per_second = 300.0 * jitter;
     let delay_second = 0.0;
     let mut transform = Transform::default();
     transform.set_translation_x(enemy_pos.x);
@@ -302,7 +1029,7 @@ fn boss_die_anime<'a>(entities: &Entities<'a>,
         }, &mut animations.1)
         .build();
     let last_seconds = 4.75;
-    let spread_per_second = 375.0;
+    let spread_per_second = 375.0 * jitter;
     let delay_second = 0.25;
     transform.set_translation_x(enemy_pos.x - 50.0);
     transform.set_translation_y(enemy_pos.y + 50.0);
@@ -343,7 +1070,7 @@ fn boss_die_anime<'a>(entities: &Entities<'a>,
         .build();
 
     let last_seconds = 4.0;
-    let spread_per_second = 500.0;
+    let spread_per_second = 500.0 * jitter;
     let delay_second = 1.0;
     transform.set_translation_x(enemy_pos.x);
     transform.set_translation_y(enemy_pos.y);
@@ -357,7 +1084,196 @@ fn boss_die_anime<'a>(entities: &Entities<'a>,
         .build();
 }
 
-pub fn is_out_of_game(tran: &Transform) -> bool {
+pub fn is_out_of_game(tran: &Transform, playfield: &Playfield) -> bool {
     let tran = tran.translation();
-    tran.x < -100.0 || tran.x > 1700.0 || tran.y > 1000.0 || tran.y < -100.0
+    tran.x < playfield.min_x - playfield.cull_margin || tran.x > playfield.max_x + playfield.cull_margin
+        || tran.y > playfield.max_y + playfield.cull_margin || tran.y < playfield.min_y - playfield.cull_margin
+}
+
+fn spawn_wall(data: &mut GameSystemData, x: f32, y: f32, half_w: f32, half_h: f32) {
+    let mut pos = Transform::default();
+    pos.set_translation_xyz(x, y, 0.0);
+    data.entities.build_entity()
+        .with(pos, &mut data.transforms)
+        .with(Wall { collide: CollideType::Aabb(half_w, half_h) }, &mut data.walls)
+        .build();
+}
+
+/// Spawns four static `Wall` colliders flush against the arena edges so
+/// scripts can bounce bullets off the boundary via the usual
+/// `CollideType::Aabb` collision tests instead of re-checking the rect by hand.
+fn spawn_walls(data: &mut GameSystemData, playfield: &Playfield) {
+    let t = playfield.wall_thickness;
+    let width = playfield.max_x - playfield.min_x;
+    let height = playfield.max_y - playfield.min_y;
+    let cx = (playfield.min_x + playfield.max_x) / 2.0;
+    let cy = (playfield.min_y + playfield.max_y) / 2.0;
+
+    spawn_wall(data, cx, playfield.min_y - t / 2.0, width / 2.0 + t, t / 2.0);
+    spawn_wall(data, cx, playfield.max_y + t / 2.0, width / 2.0 + t, t / 2.0);
+    spawn_wall(data, playfield.min_x - t / 2.0, cy, t / 2.0, height / 2.0 + t);
+    spawn_wall(data, playfield.max_x + t / 2.0, cy, t / 2.0, height / 2.0 + t);
+}
+
+/// Applies acceleration to velocity, rotates velocity by the angular term,
+/// translates the transform by the resulting velocity, and keeps the
+/// transform's heading synced to the direction of travel.
+fn integrate_bullet_motion(bullet: &mut EnemyBullet, tran: &mut Transform) {
+    bullet.vel.x += bullet.accel.x;
+    bullet.vel.y += bullet.accel.y;
+
+    if bullet.angular != 0.0 {
+        let angular_rad = bullet.angular / 180.0 * PI;
+        let (sin, cos) = angular_rad.sin_cos();
+        let (vx, vy) = (bullet.vel.x, bullet.vel.y);
+        bullet.vel.x = vx * cos - vy * sin;
+        bullet.vel.y = vx * sin + vy * cos;
+    }
+
+    if bullet.vel.x != 0.0 || bullet.vel.y != 0.0 {
+        let (x, y) = (tran.translation().x, tran.translation().y);
+        tran.set_translation_x(x + bullet.vel.x)
+            .set_translation_y(y + bullet.vel.y)
+            .set_rotation_z_axis(bullet.vel.y.atan2(bullet.vel.x));
+    }
+}
+
+/// Reflects whichever velocity component is perpendicular to the wall that
+/// was hit -- inferred from *that wall's* own aspect ratio (the top/bottom
+/// walls are wide and thin, so they flip `vel.y`; the left/right walls are
+/// narrow and tall, so they flip `vel.x`) -- and clamps the bullet's
+/// position back inside the arena.
+///
+/// This takes the hit wall's shape straight from the caller's contact test
+/// rather than re-deriving "did we hit something" from the bullet's raw
+/// center against the playfield edge: for any bullet with a nonzero
+/// collider radius, that second check disagreed with the contact test by
+/// about one radius and let bullets sail straight through the wall.
+fn bounce_off_wall(bullet: &mut EnemyBullet, tran: &mut Transform, wall_collide: &CollideType, playfield: &Playfield) {
+    if let CollideType::Aabb(half_w, half_h) = wall_collide {
+        if half_w >= half_h {
+            bullet.vel.y = -bullet.vel.y;
+        } else {
+            bullet.vel.x = -bullet.vel.x;
+        }
+    }
+    let (x, y) = (tran.translation().x, tran.translation().y);
+    tran.set_translation_x(playfield.clamp_x(x))
+        .set_translation_y(playfield.clamp_y(y));
+}
+
+/// Turns the bullet's velocity toward the player, clamping the per-tick
+/// heading change to `turn_rate` degrees so it curves instead of snapping.
+fn home_to_player(bullet: &mut EnemyBullet, tran: &Transform, player_tran: &Option<Transform>, turn_rate: f32, max_speed: f32) {
+    let player_tran = match player_tran {
+        Some(player_tran) => player_tran,
+        None => return,
+    };
+    let to_player = player_tran.translation() - tran.translation();
+    let target_heading = to_player.y.atan2(to_player.x);
+    let current_speed = bullet.vel.x.hypot(bullet.vel.y);
+    // A stationary bullet (the common case right after `SummonBullet`, which
+    // always spawns with zero velocity) has no meaningful heading to turn
+    // from, so snap straight at the player instead of turning from a heading
+    // of 0 -- and give it a speed to turn with, or it would rotate in place
+    // forever without moving.
+    let current_heading = if current_speed == 0.0 {
+        target_heading
+    } else {
+        bullet.vel.y.atan2(bullet.vel.x)
+    };
+
+    let mut delta = target_heading - current_heading;
+    while delta > PI {
+        delta -= 2.0 * PI;
+    }
+    while delta < -PI {
+        delta += 2.0 * PI;
+    }
+    let turn_rate_rad = turn_rate / 180.0 * PI;
+    let applied = delta.max(-turn_rate_rad).min(turn_rate_rad);
+    let new_heading = current_heading + applied;
+
+    let speed = if current_speed == 0.0 { max_speed } else { current_speed.min(max_speed) };
+    bullet.vel.x = new_heading.cos() * speed;
+    bullet.vel.y = new_heading.sin() * speed;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn point_vs_capsule_is_collide_with_is_symmetric() {
+        // A capsule lying along y at (0, 0), spine half-length 20, radius 5.
+        let capsule = CollideType::Capsule(20.0, 5.0);
+        let capsule_pos = Vector3::new(0.0, 0.0, 0.0);
+        // Degenerate zero-radius circle, i.e. a bare point, off to the side
+        // of the spine but within its radius.
+        let point = CollideType::Circle(0.0, 0.0);
+        let point_pos = Vector3::new(3.0, 10.0, 0.0);
+        assert!(point.is_collide_with(&point_pos, &capsule, &capsule_pos));
+        assert!(capsule.is_collide_with(&capsule_pos, &point, &point_pos));
+
+        // And a point safely outside the capsule's radius.
+        let far_point_pos = Vector3::new(20.0, 10.0, 0.0);
+        assert!(!point.is_collide_with(&far_point_pos, &capsule, &capsule_pos));
+        assert!(!capsule.is_collide_with(&capsule_pos, &point, &far_point_pos));
+    }
+
+    #[test]
+    fn circle_vs_aabb_hits_corner() {
+        let circle = Vector3::new(110.0, 110.0, 0.0);
+        let aabb = Vector3::new(0.0, 0.0, 0.0);
+        assert!(circle_vs_aabb(&circle, 15.0, &aabb, 100.0, 100.0));
+        assert!(!circle_vs_aabb(&circle, 5.0, &aabb, 100.0, 100.0));
+    }
+
+    #[test]
+    fn circle_vs_aabb_hits_flat_edge() {
+        let circle = Vector3::new(0.0, 108.0, 0.0);
+        let aabb = Vector3::new(0.0, 0.0, 0.0);
+        assert!(circle_vs_aabb(&circle, 10.0, &aabb, 100.0, 100.0));
+        assert!(!circle_vs_aabb(&circle, 5.0, &aabb, 100.0, 100.0));
+    }
+
+    #[test]
+    fn capsule_vs_circle_hits_along_spine() {
+        let capsule = Vector3::new(0.0, 0.0, 0.0);
+        let circle = Vector3::new(8.0, 20.0, 0.0);
+        assert!(capsule_vs_circle(&capsule, 20.0, 5.0, &circle, 4.0));
+        assert!(!capsule_vs_circle(&capsule, 20.0, 5.0, &circle, 1.0));
+    }
+
+    #[test]
+    fn capsule_vs_circle_hits_past_the_cap() {
+        let capsule = Vector3::new(0.0, 0.0, 0.0);
+        let circle = Vector3::new(0.0, 30.0, 0.0);
+        assert!(capsule_vs_circle(&capsule, 20.0, 5.0, &circle, 6.0));
+        assert!(!capsule_vs_circle(&capsule, 20.0, 5.0, &circle, 4.0));
+    }
+
+    #[test]
+    fn capsule_vs_aabb_hits_along_spine() {
+        let capsule = Vector3::new(0.0, 0.0, 0.0);
+        let aabb = Vector3::new(30.0, 0.0, 0.0);
+        assert!(capsule_vs_aabb(&capsule, 20.0, 25.0, &aabb, 10.0, 10.0));
+        assert!(!capsule_vs_aabb(&capsule, 20.0, 4.0, &aabb, 10.0, 10.0));
+    }
+
+    #[test]
+    fn capsule_vs_capsule_parallel_spines_overlap() {
+        let a = Vector3::new(0.0, 0.0, 0.0);
+        let b = Vector3::new(12.0, 5.0, 0.0);
+        assert!(capsule_vs_capsule(&a, 20.0, 8.0, &b, 20.0, 8.0));
+        assert!(!capsule_vs_capsule(&a, 20.0, 2.0, &b, 20.0, 2.0));
+    }
+
+    #[test]
+    fn capsule_vs_capsule_past_the_caps() {
+        let a = Vector3::new(0.0, 0.0, 0.0);
+        let b = Vector3::new(0.0, 45.0, 0.0);
+        assert!(capsule_vs_capsule(&a, 15.0, 8.0, &b, 15.0, 8.0));
+        assert!(!capsule_vs_capsule(&a, 15.0, 4.0, &b, 15.0, 4.0));
+    }
 }
\ No newline at end of file